@@ -0,0 +1,23 @@
+//! Benchmark for [`check_hash`], demonstrating the effect of reusing a single
+//! buffer across hashing rounds instead of allocating one per round.
+//!
+//! Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use phpbb_pwhash::{check_hash, CheckHashResult};
+
+fn bench_check_hash(c: &mut Criterion) {
+    // A '9' hash uses 2^11 rounds, so the inner loop dominates the runtime.
+    let hash = "$H$9/O41.qQjQNlleivjbckbSNpfS4xgh0";
+    c.bench_function("check_hash 2^11 rounds", |b| {
+        b.iter(|| {
+            assert_eq!(
+                check_hash(criterion::black_box(hash), criterion::black_box("pass1234")),
+                CheckHashResult::Valid
+            );
+        })
+    });
+}
+
+criterion_group!(benches, bench_check_hash);
+criterion_main!(benches);
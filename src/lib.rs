@@ -19,6 +19,9 @@
 //! );
 //! ```
 
+use rand::Rng;
+use subtle::ConstantTimeEq;
+
 /// The result type returned by [`check_hash`](crate::check_hash).
 #[derive(Debug, PartialEq)]
 pub enum CheckHashResult {
@@ -49,6 +52,35 @@ pub struct PhpbbHash<'a> {
 // Base64 alphabet
 static ALPHABET: &str = "./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
+/// Strip a leading RFC 2307 `{CRYPT}` scheme prefix, if present.
+///
+/// Directory systems store phpass/phpBB hashes wrapped as `{CRYPT}$H$...` in
+/// the `userPassword` attribute. The prefix is matched case-insensitively.
+fn strip_rfc2307(salted_hash: &str) -> &str {
+    match salted_hash.get(..7) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("{CRYPT}") => &salted_hash[7..],
+        _ => salted_hash,
+    }
+}
+
+impl<'a> PhpbbHash<'a> {
+    /// Parse a hash wrapped in the RFC 2307 `{CRYPT}` scheme, as stored in an
+    /// LDAP `userPassword` attribute.
+    pub fn from_rfc2307(wrapped: &'a str) -> Result<PhpbbHash<'a>, InvalidHash> {
+        parse_hash(strip_rfc2307(wrapped))
+    }
+
+    /// Render this hash in RFC 2307 `userPassword` form, i.e. wrapped in a
+    /// `{CRYPT}` scheme prefix.
+    pub fn as_rfc2307(&self) -> String {
+        let rounds_char = ALPHABET.as_bytes()[self.rounds.trailing_zeros() as usize] as char;
+        format!(
+            "{{CRYPT}}{}{}{}{}",
+            self.hash_type, rounds_char, self.salt, self.hashed
+        )
+    }
+}
+
 /// Parse a phpBB3 hash.
 ///
 /// A hash for the password "pass1234" can look like this:
@@ -59,7 +91,9 @@ static ALPHABET: &str = "./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopq
 ///
 /// Details:
 ///
-/// - The first three characters are the hash type, should be '$H$'.
+/// - The first three characters are the hash type, either '$H$' (phpBB) or
+///   '$P$' (phpass/WordPress). The parsed identifier is preserved on the
+///   returned [`PhpbbHash`] so callers can round-trip it.
 /// - The fourth character encodes the number of hashing rounds, as a power of
 ///   two. For example, if the value is '9' as above, then (1 << 11) rounds are
 ///   used (because the offset from the start of the alphabet for '9' is 11).
@@ -67,14 +101,19 @@ static ALPHABET: &str = "./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopq
 /// - Characters 5-13 are the 8-byte salt.
 /// - Characters 13 and onwards are the encoded hash.
 pub fn parse_hash(salted_hash: &str) -> Result<PhpbbHash, InvalidHash> {
+    // Transparently unwrap an RFC 2307 `{CRYPT}` prefix (LDAP interop).
+    let salted_hash = strip_rfc2307(salted_hash);
+
     // Check for unsalted MD5 hashes
     if salted_hash.len() != 34 {
         return Err(InvalidHash::BadLength);
     }
 
-    // Validate prefix
+    // Validate prefix. phpBB uses '$H$', but the scheme was inherited from
+    // Solar Designer's phpass, which uses '$P$' (as do WordPress and others);
+    // the body format is identical, so both identifiers are accepted.
     let hash_type = &salted_hash[0..3];
-    if hash_type != "$H$" {
+    if hash_type != "$H$" && hash_type != "$P$" {
         return Err(InvalidHash::UnsupportedHashType);
     };
 
@@ -97,6 +136,97 @@ pub fn parse_hash(salted_hash: &str) -> Result<PhpbbHash, InvalidHash> {
     })
 }
 
+/// Encoding function.
+///
+/// Encode a 16-byte MD5 digest into the phpBB base64 variant. This is the
+/// inverse of [`decode64`]: bytes are processed in little-endian 3-byte groups,
+/// emitting one [`ALPHABET`] character per 6 bits.
+fn encode64(val: &[u8]) -> String {
+    let itoa64 = ALPHABET.as_bytes();
+    let mut output = String::with_capacity((val.len() * 4 + 2) / 3);
+    let count = val.len();
+    let mut i = 0;
+    while i < count {
+        let mut value = val[i] as u32;
+        i += 1;
+        output.push(itoa64[(value & 0x3f) as usize] as char);
+        if i < count {
+            value |= (val[i] as u32) << 8;
+        }
+        output.push(itoa64[((value >> 6) & 0x3f) as usize] as char);
+        if i >= count {
+            break;
+        }
+        i += 1;
+        if i < count {
+            value |= (val[i] as u32) << 16;
+        }
+        output.push(itoa64[((value >> 12) & 0x3f) as usize] as char);
+        if i >= count {
+            break;
+        }
+        i += 1;
+        output.push(itoa64[((value >> 18) & 0x3f) as usize] as char);
+    }
+    output
+}
+
+/// Generate a random 8-byte salt, drawn from [`ALPHABET`] using a CSPRNG.
+fn generate_salt() -> String {
+    let itoa64 = ALPHABET.as_bytes();
+    let mut rng = rand::thread_rng();
+    (0..8)
+        .map(|_| itoa64[rng.gen_range(0..itoa64.len())] as char)
+        .collect()
+}
+
+/// Hash a password into a phpBB3 salted hash, using a random salt and a
+/// default of 2^11 hashing rounds.
+///
+/// The salt is drawn from a CSPRNG, so the returned hash differs on every call.
+/// Use [`hash_with`] if you need to control the salt or the round count (for
+/// example to reproduce a known hash in tests).
+pub fn hash(password: &str) -> String {
+    hash_with(&generate_salt(), 11, password)
+}
+
+/// Hash a password into a phpBB3 salted hash with an explicit salt and round
+/// count.
+///
+/// The `salt` must be 8 bytes drawn from [`ALPHABET`], and `rounds_log2`
+/// encodes the number of hashing rounds as a power of two (so `1 << rounds_log2`
+/// rounds are performed). `rounds_log2` is clamped to the 7..=30 range that
+/// [`parse_hash`] accepts, so the returned hash is always verifiable and
+/// out-of-range values can neither overflow the shift nor panic. This mirrors
+/// the parameterized hashing done by [`check_hash`].
+pub fn hash_with(salt: &str, rounds_log2: u8, password: &str) -> String {
+    // Clamp into the range parse_hash accepts (see its docs). This keeps the
+    // shift and the ALPHABET index in bounds and guarantees a verifiable hash.
+    let rounds_log2 = rounds_log2.clamp(7, 30);
+
+    let password_bytes = password.as_bytes();
+    let password_bytes_len = password_bytes.len();
+
+    // Initial hash
+    let mut buf: Vec<u8> = Vec::with_capacity(8 + password_bytes_len);
+    buf.extend_from_slice(salt.as_bytes());
+    buf.extend_from_slice(password_bytes);
+    let mut hash = md5::compute(&buf);
+
+    // Some additional rounds of hashing. Reuse a single buffer whose trailing
+    // password bytes never change, rewriting only the leading 16 digest bytes
+    // each round.
+    let mut round_buf: Vec<u8> = vec![0; 16 /* md5 */ + password_bytes_len];
+    round_buf[16..].copy_from_slice(password_bytes);
+    for _ in 0..(1u64 << rounds_log2) {
+        round_buf[..16].copy_from_slice(&hash.0);
+        hash = md5::compute(&round_buf);
+    }
+
+    let rounds_char = ALPHABET.as_bytes()[rounds_log2 as usize] as char;
+    format!("$H${}{}{}", rounds_char, salt, encode64(&hash.0))
+}
+
 /// Decoding function.
 ///
 /// Code taken from phpass re-implementation by Joshua Koudys, licensed under
@@ -131,6 +261,22 @@ pub fn check_hash(salted_hash: &str, password: &str) -> CheckHashResult {
     let password_bytes = password.as_bytes();
     let password_bytes_len = password_bytes.len();
 
+    // Transparently unwrap an RFC 2307 `{CRYPT}` prefix (LDAP interop) so the
+    // wrapped form works in every branch below.
+    let salted_hash = strip_rfc2307(salted_hash);
+
+    // Fall back to the legacy unsalted form. Pre-3.0 phpBB installations store
+    // a raw 32-character lowercase hex `md5(password)` digest, and
+    // `phpbb_check_hash` still accepts it for migrated accounts.
+    if salted_hash.len() == 32 && salted_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let hex = format!("{:x}", md5::compute(password_bytes));
+        return if bool::from(hex.as_bytes().ct_eq(salted_hash.as_bytes())) {
+            CheckHashResult::Valid
+        } else {
+            CheckHashResult::Invalid
+        };
+    }
+
     // Parse salted hash
     let parsed = match parse_hash(salted_hash) {
         Ok(p) => p,
@@ -149,16 +295,19 @@ pub fn check_hash(salted_hash: &str, password: &str) -> CheckHashResult {
     buf.extend_from_slice(password.as_bytes());
     let mut hash = md5::compute(&buf);
 
-    // Some additional rounds of hashing
-    // (Yeah, this re-allocates a buffer for every round, could be improved.)
+    // Some additional rounds of hashing. Reuse a single buffer whose trailing
+    // password bytes never change, rewriting only the leading 16 digest bytes
+    // each round. This avoids an allocation on every one of up to ~2^30 rounds.
+    let mut round_buf: Vec<u8> = vec![0; 16 /* md5 */ + password_bytes_len];
+    round_buf[16..].copy_from_slice(password_bytes);
     for _ in 0..parsed.rounds {
-        let mut buf: Vec<u8> = Vec::with_capacity(16 /* md5 */ + password_bytes_len);
-        buf.extend_from_slice(&hash.0);
-        buf.extend_from_slice(password_bytes);
-        hash = md5::compute(&buf);
+        round_buf[..16].copy_from_slice(&hash.0);
+        hash = md5::compute(&round_buf);
     }
 
-    if hash.0.as_ref() == decoded_hashed {
+    // Compare in constant time so that the duration of the check does not
+    // leak how many leading bytes of the digest matched.
+    if bool::from(hash.0.as_ref().ct_eq(&decoded_hashed)) {
         CheckHashResult::Valid
     } else {
         CheckHashResult::Invalid
@@ -194,11 +343,34 @@ mod tests {
                 password: "pass1234",
                 result: CheckHashResult::Valid,
             },
+            TestCase {
+                // Same body with the phpass '$P$' identifier is equally valid.
+                encoded_hash: "$P$9/O41.qQjQNlleivjbckbSNpfS4xgh0",
+                password: "pass1234",
+                result: CheckHashResult::Valid,
+            },
             TestCase {
                 encoded_hash: "$H$9/O41.qQjQNlleivjbckbSNpfS4xgh0",
                 password: "pass1235",
                 result: CheckHashResult::Invalid,
             },
+            TestCase {
+                // RFC 2307 wrapped form, as stored by LDAP directories.
+                encoded_hash: "{CRYPT}$H$9/O41.qQjQNlleivjbckbSNpfS4xgh0",
+                password: "pass1234",
+                result: CheckHashResult::Valid,
+            },
+            TestCase {
+                // Legacy pre-3.0 unsalted md5(password) hex digest.
+                encoded_hash: "b4af804009cb036a4ccdc33431ef9ac9",
+                password: "pass1234",
+                result: CheckHashResult::Valid,
+            },
+            TestCase {
+                encoded_hash: "b4af804009cb036a4ccdc33431ef9ac9",
+                password: "pass1235",
+                result: CheckHashResult::Invalid,
+            },
             TestCase {
                 encoded_hash: "$H$9/O41.qQjQNlleivjbckbSNpfS4xgh012",
                 password: "pass1234",
@@ -220,4 +392,45 @@ mod tests {
             assert_eq!(result, case.result, "{:?}", case);
         }
     }
+
+    #[test]
+    fn test_hash_with_matches_known_vector() {
+        // Re-create a known hash from its salt and round count.
+        let hashed = hash_with("/O41.qQj", 11, "pass1234");
+        assert_eq!(hashed, "$H$9/O41.qQjQNlleivjbckbSNpfS4xgh0");
+    }
+
+    #[test]
+    fn test_rfc2307_roundtrip() {
+        let wrapped = "{CRYPT}$H$9/O41.qQjQNlleivjbckbSNpfS4xgh0";
+        let parsed = PhpbbHash::from_rfc2307(wrapped).unwrap();
+        assert_eq!(parsed.as_rfc2307(), wrapped);
+    }
+
+    #[test]
+    fn test_rfc2307_mixed_case_prefix() {
+        assert_eq!(
+            check_hash("{Crypt}$H$9/O41.qQjQNlleivjbckbSNpfS4xgh0", "pass1234"),
+            CheckHashResult::Valid
+        );
+    }
+
+    #[test]
+    fn test_hash_with_clamps_rounds() {
+        // An out-of-range round count must not panic and must stay verifiable;
+        // clamp the low side so the test stays cheap (2^7 rounds).
+        let hashed = hash_with("/O41.qQj", 0, "pass1234");
+        assert_eq!(&hashed[..4], "$H$5"); // ALPHABET[7] == '5'
+        assert_eq!(check_hash(&hashed, "pass1234"), CheckHashResult::Valid);
+    }
+
+    #[test]
+    fn test_hash_roundtrips() {
+        let hashed = hash("correct horse battery staple");
+        assert_eq!(
+            check_hash(&hashed, "correct horse battery staple"),
+            CheckHashResult::Valid
+        );
+        assert_eq!(check_hash(&hashed, "wrong password"), CheckHashResult::Invalid);
+    }
 }